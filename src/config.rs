@@ -1,10 +1,16 @@
 use anyhow::Result;
+use std::collections::HashMap;
 use std::env;
-use std::path::{Path, PathBuf};
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
 
 #[derive(Clone, Debug)]
 pub struct Configuration {
     pub root_dir: Option<PathBuf>,
+    // Extension (lowercase, no leading dot) -> Content-Type, consulted
+    // before the built-in table in `mime::content_type_for` so operators
+    // can register types it doesn't know about or override its defaults.
+    pub mime_overrides: HashMap<String, String>,
 }
 
 impl Configuration {
@@ -20,15 +26,42 @@ impl Configuration {
 
         Self {
             root_dir: directory,
+            mime_overrides: HashMap::new(),
         }
     }
 
+    /// Registers (or overrides) the `Content-Type` served for a file
+    /// extension, e.g. `register_mime_type("heic", "image/heic")`.
+    pub fn register_mime_type(&mut self, extension: &str, content_type: &str) {
+        self.mime_overrides.insert(extension.to_lowercase(), content_type.to_string());
+    }
+
+    /// Resolves a request path against the served root, dropping any `..`,
+    /// `.` or root components from `path` so the result can never climb out
+    /// of the root through the path itself. As a second line of defense
+    /// against a symlink inside the root pointing outside it, the resolved
+    /// path is also rejected if canonicalizing it escapes the canonicalized
+    /// root (a path that doesn't exist yet, e.g. an upload target, skips
+    /// that check since there's nothing to canonicalize).
     pub fn resolve_path(&self, path: &Path) -> Result<PathBuf> {
-        let mut full_path = match &self.root_dir {
+        let base_dir = match &self.root_dir {
             Some(base_dir) => base_dir.clone(),
             None => env::current_dir()?,
         };
-        full_path.push(path);
+
+        let mut full_path = base_dir.clone();
+        for component in path.components() {
+            if let Component::Normal(segment) = component {
+                full_path.push(segment);
+            }
+        }
+
+        if let Ok(canonical_full) = full_path.canonicalize() {
+            let canonical_base = base_dir.canonicalize().unwrap_or(base_dir);
+            if !canonical_full.starts_with(canonical_base) {
+                return crate::build_error(ErrorKind::PermissionDenied, "Path escapes the served root directory");
+            }
+        }
 
         Ok(full_path)
     }
@@ -1,26 +1,41 @@
 use crate::{
     build_error,
     config::Configuration,
-    request::Request,
+    error_kind, http_date, mime,
+    request::{Encoding, Request},
     response::Response,
-    {Payload, PinnedReturn, Reader, StatusCode, Writer},
+    {copy_bytes, Payload, PinnedReturn, StatusCode, COPY_BUFFER_DEFAULT_SIZE},
 };
 use anyhow::Result;
-use async_compression::tokio::bufread::GzipEncoder;
-use std::io::{ErrorKind, Cursor};
-use std::os::unix::ffi::OsStrExt;
+use async_compression::tokio::bufread::{DeflateEncoder, GzipEncoder};
+use std::io::{Cursor, ErrorKind, SeekFrom};
+use std::path::Path;
 use tokio::fs::File;
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, BufReader};
+
+/// The `/files/` routes are declared with a `*path` catch-all, so the file
+/// targeted by the request is the captured `path` param, not the request
+/// path itself (which still carries the `/files/` prefix).
+fn target_path<'a>(request: &'a Request) -> &'a Path {
+    Path::new(request.param("path").unwrap_or(""))
+}
 
 pub fn handle_echo<'a>(_config: &Configuration, request: Request<'a>) -> PinnedReturn<'a> {
     Box::pin(async move {
-        let raw_text = request.path().as_os_str().as_bytes().to_vec();
-        let text = if request.wants_gzip_encoding() {
-            let mut buf = vec![];
-            let _ = GzipEncoder::new(Cursor::new(raw_text)).read_to_end(&mut buf).await;
-            buf
-        } else {
-            raw_text
+        let raw_text = request.param("word").unwrap_or("").as_bytes().to_vec();
+        let encoding = request.negotiate_encoding();
+        let text = match encoding {
+            Encoding::Gzip => {
+                let mut buf = vec![];
+                let _ = GzipEncoder::new(Cursor::new(raw_text)).read_to_end(&mut buf).await;
+                buf
+            }
+            Encoding::Deflate => {
+                let mut buf = vec![];
+                let _ = DeflateEncoder::new(Cursor::new(raw_text)).read_to_end(&mut buf).await;
+                buf
+            }
+            Encoding::Identity => raw_text,
         };
         let tlen = text.len();
         let length = tlen.to_string();
@@ -28,11 +43,15 @@ pub fn handle_echo<'a>(_config: &Configuration, request: Request<'a>) -> PinnedR
 
         let mut response = Response::ok(payload);
         response.add_header("Content-Type", "text/plain");
-
-        if tlen > 0 {
-            response.add_header("Content-Length", &length);
+        if encoding != Encoding::Identity {
+            response.add_header("Content-Encoding", encoding.token());
         }
 
+        // Always send Content-Length, even "0" -- a Simple payload never
+        // triggers chunked framing, so it's the only body delimiter a
+        // keep-alive client gets.
+        response.add_header("Content-Length", &length);
+
         Ok(response)
     })
 }
@@ -43,9 +62,8 @@ pub fn handle_user_agent<'a>(_config: &Configuration, request: Request<'a>) -> P
             let length = agent.len().to_string();
             let mut response = Response::ok(Payload::Simple(vec![agent.as_bytes().to_owned()]));
             response.add_header("Content-Type", "text/plain");
-            if agent.len() > 0 {
-                response.add_header("Content-Length", &length);
-            }
+            // Always send Content-Length, even "0" -- see handle_echo.
+            response.add_header("Content-Length", &length);
 
             Ok(response)
         } else {
@@ -57,24 +75,121 @@ pub fn handle_user_agent<'a>(_config: &Configuration, request: Request<'a>) -> P
     })
 }
 
+/// Parses a single `Range: bytes=...` spec against a known file size,
+/// returning the inclusive `(start, end)` byte offsets to serve, or `Err(())`
+/// if the range cannot be satisfied for that size.
+fn parse_range(spec: &str, size: u64) -> Result<(u64, u64), ()> {
+    let spec = spec.strip_prefix("bytes=").ok_or(())?;
+    let (start_s, end_s) = spec.split_once('-').ok_or(())?;
+
+    if start_s.is_empty() {
+        // Suffix form: bytes=-N, meaning "the last N bytes".
+        let suffix_len: u64 = end_s.parse().map_err(|_| ())?;
+        if suffix_len == 0 || size == 0 {
+            return Err(());
+        }
+        let len = suffix_len.min(size);
+        Ok((size - len, size - 1))
+    } else {
+        let start: u64 = start_s.parse().map_err(|_| ())?;
+        if start >= size {
+            return Err(());
+        }
+        let end = if end_s.is_empty() {
+            size - 1
+        } else {
+            end_s.parse::<u64>().map_err(|_| ())?.min(size - 1)
+        };
+        if end < start {
+            return Err(());
+        }
+        Ok((start, end))
+    }
+}
+
 pub fn handle_download_file<'a>(
     config: &'a Configuration,
     request: Request<'a>,
 ) -> PinnedReturn<'a> {
     Box::pin(async move {
-        let full_path = config.resolve_path(request.path())?;
+        let full_path = match config.resolve_path(target_path(&request)) {
+            Ok(path) => path,
+            Err(error) => match error_kind(&error) {
+                Some(ErrorKind::PermissionDenied) => return Ok(Response::forbidden()),
+                _ => return Err(error),
+            },
+        };
 
         match File::open(full_path).await {
             Ok(file) => {
-                let size = file.metadata().await?.len();
-                let buf_reader = BufReader::new(file);
+                let metadata = file.metadata().await?;
+                let size = metadata.len();
+                let mtime = metadata.modified()?;
+                let mtime_secs = mtime.duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs();
+                let etag = format!("W/\"{}-{}\"", size, mtime_secs);
+                let last_modified = http_date::format_http_date(mtime);
+
+                // If-None-Match takes precedence over If-Modified-Since per
+                // RFC 7232, so we only fall back to the date-based check when
+                // the client didn't send an ETag to validate against.
+                let not_modified = match request.if_none_match() {
+                    Some(if_none_match) => if_none_match == etag,
+                    None => request
+                        .if_modified_since()
+                        .map(|since| mtime.duration_since(since).map(|d| d.is_zero()).unwrap_or(true))
+                        .unwrap_or(false),
+                };
+
+                if not_modified {
+                    let mut response = Response::from_status(StatusCode::NotModified);
+                    response.add_header("ETag", &etag);
+                    response.add_header("Last-Modified", &last_modified);
+                    return Ok(response);
+                }
+
+                let mut buf_reader = BufReader::new(file);
+                let content_type = mime::content_type_for(target_path(&request), &config.mime_overrides);
+                let disposition = if mime::is_inline_renderable(&content_type) {
+                    "inline"
+                } else {
+                    "attachment"
+                };
+
+                if let Some(range_spec) = request.range_header() {
+                    return match parse_range(&range_spec, size) {
+                        Ok((start, end)) => {
+                            buf_reader.seek(SeekFrom::Start(start)).await?;
+                            let len = (end - start + 1) as usize;
+                            let mut response = Response::from_status(StatusCode::PartialContent);
+                            response.set_payload(Payload::ReadStream(Box::new(buf_reader), Some(len)));
+                            response.add_header("Content-Range", &format!("bytes {}-{}/{}", start, end, size));
+                            response.add_header("Content-Length", &len.to_string());
+                            response.add_header("Content-Type", &content_type);
+                            response.add_header("Content-Disposition", disposition);
+                            response.add_header("ETag", &etag);
+                            response.add_header("Last-Modified", &last_modified);
+                            Ok(response)
+                        }
+                        Err(()) => {
+                            let mut response = Response::from_status(StatusCode::RangeNotSatisfiable);
+                            response.add_header("Content-Range", &format!("bytes */{}", size));
+                            Ok(response)
+                        }
+                    };
+                }
+
                 let mut response =
-                    Response::ok(Payload::ReadStream(Box::new(buf_reader)));
+                    Response::ok(Payload::ReadStream(Box::new(buf_reader), None));
                 if size > 0 {
                     response.add_header("Content-Length", &size.to_string());
                 }
-                response.add_header("Content-Type", "application/octet-stream");
-                response.add_header("Content-Disposition", "attachment");
+                response.add_header("Content-Type", &content_type);
+                response.add_header("Content-Disposition", disposition);
+                response.add_header("ETag", &etag);
+                response.add_header("Last-Modified", &last_modified);
+                // The actual Range/206/416 handling lives in the branch
+                // above; this just advertises that it's there.
+                response.add_header("Accept-Ranges", "bytes");
                 Ok(response)
             }
             Err(error) => match error.kind() {
@@ -86,24 +201,104 @@ pub fn handle_download_file<'a>(
     })
 }
 
-const COPY_BUFFER_DEFAULT_SIZE: usize = 1024;
-
-async fn copy_bytes<'a>(
-    reader: &mut Reader<'a>,
-    writer: &mut Writer,
-    len: usize,
-    buf_size: usize,
-) -> Result<usize> {
-    let mut remaining = len;
+/// Percent-encodes a single path segment for use in an `href`, escaping
+/// everything outside the URI "unreserved" set (RFC 3986 2.3) -- which
+/// already rules out quotes, angle brackets, and `&` ending up literal in
+/// the attribute.
+fn percent_encode(segment: &str) -> String {
+    let mut out = String::with_capacity(segment.len());
+    for byte in segment.as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(*byte as char)
+            }
+            byte => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
 
-    while remaining > 0 {
-        let mut buffer = vec![0; std::cmp::min(buf_size, remaining)];
-        remaining -= reader.read_exact(&mut buffer).await?;
-        writer.write_all(&buffer).await?;
+/// Escapes the characters that are special in HTML text and attribute
+/// values, so an entry name can't break out of the markup it's
+/// interpolated into.
+fn html_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(ch),
+        }
     }
-    writer.flush().await?;
+    out
+}
+
+pub fn handle_serve_dir<'a>(config: &'a Configuration, request: Request<'a>) -> PinnedReturn<'a> {
+    Box::pin(async move {
+        let full_path = match config.resolve_path(target_path(&request)) {
+            Ok(path) => path,
+            Err(error) => match error_kind(&error) {
+                Some(ErrorKind::PermissionDenied) => return Ok(Response::forbidden()),
+                _ => return Err(error),
+            },
+        };
+
+        let metadata = match tokio::fs::metadata(&full_path).await {
+            Ok(metadata) => metadata,
+            Err(error) => {
+                return match error.kind() {
+                    ErrorKind::NotFound => Ok(Response::not_found()),
+                    ErrorKind::PermissionDenied => Ok(Response::forbidden()),
+                    _ => Ok(Response::internal_error()),
+                }
+            }
+        };
+
+        if metadata.is_file() {
+            return handle_download_file(config, request).await;
+        }
+
+        if tokio::fs::metadata(full_path.join("index.html")).await.is_ok() {
+            let index_path = request.path().join("index.html");
+            let index_param = target_path(&request).join("index.html");
+            let mut index_request = Request::with_path(request, index_path);
+            index_request.set_param("path", index_param.to_string_lossy().into_owned());
+            return handle_download_file(config, index_request).await;
+        }
+
+        let mut entries = Vec::new();
+        let mut read_dir = tokio::fs::read_dir(&full_path).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let file_type = entry.file_type().await?;
+            let size = entry.metadata().await?.len();
+            entries.push((entry.file_name(), file_type.is_dir(), size));
+        }
+        entries.sort_by(|(name_a, dir_a, _), (name_b, dir_b, _)| {
+            dir_b.cmp(dir_a).then_with(|| name_a.cmp(name_b))
+        });
 
-    Ok(len - remaining)
+        let mut html = String::from("<!DOCTYPE html>\n<html>\n<body>\n<ul>\n");
+        for (name, is_dir, size) in entries {
+            let name = name.to_string_lossy();
+            let href = percent_encode(&name);
+            let label = html_escape(&name);
+            if is_dir {
+                html.push_str(&format!("<li><a href=\"{href}/\">{label}/</a></li>\n"));
+            } else {
+                html.push_str(&format!("<li><a href=\"{href}\">{label}</a> ({size} bytes)</li>\n"));
+            }
+        }
+        html.push_str("</ul>\n</body>\n</html>\n");
+
+        let length = html.len().to_string();
+        let mut response = Response::ok(Payload::Simple(vec![html.into_bytes()]));
+        response.add_header("Content-Type", "text/html");
+        response.add_header("Content-Length", &length);
+        Ok(response)
+    })
 }
 
 pub fn handle_upload_file<'a>(
@@ -111,15 +306,21 @@ pub fn handle_upload_file<'a>(
     mut request: Request<'a>,
 ) -> PinnedReturn<'a> {
     Box::pin(async move {
-        let full_path = config.resolve_path(request.path())?;
+        let full_path = match config.resolve_path(target_path(&request)) {
+            Ok(path) => path,
+            Err(error) => match error_kind(&error) {
+                Some(ErrorKind::PermissionDenied) => return Ok(Response::forbidden()),
+                _ => return Err(error),
+            },
+        };
 
         match File::create(full_path).await {
             Ok(mut file) => {
-                if let (Some(length), Some(Payload::ReadStream(mut reader))) =
+                if let (Some(length), Some(Payload::ReadStream(mut reader, _))) =
                     (request.content_length(), request.body())
                 {
                     // TODO: Should probably check the actual read size
-                    copy_bytes(&mut reader, &mut file, length, COPY_BUFFER_DEFAULT_SIZE).await?;
+                    copy_bytes(&mut *reader, &mut file, length, COPY_BUFFER_DEFAULT_SIZE).await?;
                     Ok(Response::from_status(StatusCode::Created))
                 } else {
                     Ok(Response::internal_error())
@@ -1,13 +1,16 @@
 use crate::config::Configuration;
 use crate::request::Request;
 use anyhow::Result;
+use std::cmp::min;
 use std::future::Future;
 use std::io::ErrorKind;
 use std::pin::Pin;
-use tokio::io::{self, AsyncBufRead, AsyncWrite};
+use tokio::io::{self, AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 pub mod config;
 pub mod handlers;
+pub mod http_date;
+pub mod mime;
 pub mod request;
 pub mod response;
 pub mod route;
@@ -31,15 +34,21 @@ struct HeaderField {
 
 pub enum Payload<'a> {
     Simple(Vec<Vec<u8>>),
-    ReadStream(Box<Reader<'a>>),
+    // The trailing `Option<usize>` is a byte limit: `Some(n)` stops the stream
+    // after `n` bytes (e.g. a Range response), `None` reads until EOF.
+    ReadStream(Box<Reader<'a>>, Option<usize>),
 }
 
 #[derive(Clone)]
 pub enum StatusCode {
     HttpOk,
     Created,
+    PartialContent,
+    NotModified,
     NotFound,
     Forbidden,
+    RequestTimeout,
+    RangeNotSatisfiable,
     InternalServerError,
 }
 
@@ -51,6 +60,67 @@ pub enum HttpVerb {
     Post,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum HttpVersion {
+    Http10,
+    Http11,
+}
+
 pub fn build_error<T>(kind: ErrorKind, msg: &str) -> Result<T> {
     Err(io::Error::new(kind, msg).into())
 }
+
+/// Recovers the `io::ErrorKind` an `anyhow::Error` was built from via
+/// `build_error`, so callers can branch on it the same way they do for
+/// errors returned directly by `tokio::fs`/`tokio::io`.
+pub fn error_kind(error: &anyhow::Error) -> Option<ErrorKind> {
+    error.downcast_ref::<io::Error>().map(|error| error.kind())
+}
+
+pub const COPY_BUFFER_DEFAULT_SIZE: usize = 1024;
+
+/// Copies exactly `len` bytes from `reader` to `writer`, in chunks of at most
+/// `buf_size` bytes.
+pub async fn copy_bytes<R, W>(reader: &mut R, writer: &mut W, len: usize, buf_size: usize) -> Result<usize>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let mut remaining = len;
+
+    while remaining > 0 {
+        let mut buffer = vec![0; min(buf_size, remaining)];
+        reader.read_exact(&mut buffer).await?;
+        writer.write_all(&buffer).await?;
+        remaining -= buffer.len();
+    }
+    writer.flush().await?;
+
+    Ok(len - remaining)
+}
+
+/// Streams `reader` to `writer` framed as `Transfer-Encoding: chunked`: each
+/// buffer read is written as `<hex-len>\r\n<data>\r\n`, followed by the
+/// terminating `0\r\n\r\n` once the reader is exhausted. Used for stream
+/// payloads whose total length isn't known up front.
+pub async fn write_chunked_body<R, W>(reader: &mut R, writer: &mut W) -> Result<()>
+where
+    R: AsyncBufRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    loop {
+        let buf = reader.fill_buf().await?;
+        let len = buf.len();
+        if len == 0 {
+            break;
+        }
+        writer.write_all(format!("{:x}\r\n", len).as_bytes()).await?;
+        writer.write_all(buf).await?;
+        writer.write_all(b"\r\n").await?;
+        reader.consume(len);
+    }
+    writer.write_all(b"0\r\n\r\n").await?;
+    writer.flush().await?;
+
+    Ok(())
+}
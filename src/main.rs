@@ -1,245 +1,85 @@
-use std::env;
-use std::ffi::OsStr;
-use std::future::Future;
+use anyhow::Result;
+use async_compression::tokio::bufread::{DeflateEncoder, GzipEncoder};
 use std::io::ErrorKind;
-use std::os::unix::ffi::OsStrExt;
 use std::path::PathBuf;
 use std::pin::Pin;
-use anyhow::Result;
-use tokio::{
-    fs::File,
-    io::{self, AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader},
-    net::{
-        tcp::WriteHalf,
-        TcpListener,
-        TcpStream,
-    }
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{self, AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+
+use codecrafters_http_server_rust::{
+    build_error, copy_bytes,
+    config::Configuration,
+    handlers::{handle_echo, handle_serve_dir, handle_upload_file, handle_user_agent},
+    request::{Encoding, Request},
+    response::Response,
+    route::{Route, RouteMatch, RouteTarget},
+    write_chunked_body, HttpVerb, HttpVersion, Payload, Reader, StatusCode,
+    COPY_BUFFER_DEFAULT_SIZE,
 };
-use tokio::io::AsyncReadExt;
-
-#[derive(Clone)]
-struct HeaderField {
-    name: String,
-    value: String,
-}
-
-#[derive(Clone)]
-enum StatusCode {
-    HttpOk,
-    Created,
-    NotFound,
-    Forbidden,
-    InternalServerError,
-}
-
-type Reader<'a> = dyn AsyncBufRead + Unpin + Send + Sync + 'a;
-type Writer = dyn AsyncWrite + Unpin + Send + Sync;
-
-enum Payload<'a> {
-    Simple(Vec<Vec<u8>>),
-    ReadStream(Box<Reader<'a>>),
-}
-
-struct Response<'a> {
-    code: StatusCode,
-    headers: Vec<HeaderField>,
-    payload: Option<Payload<'a>>,
-}
-
-impl<'a> Response<'a> {
-    fn from_status(status: StatusCode) -> Self {
-        Self {
-            code: status,
-            headers: vec![],
-            payload: None
-        }
-    }
-
-    fn ok(content: Payload<'a>) -> Self {
-        Self {
-            code: StatusCode::HttpOk,
-            headers: vec![],
-            payload: Some(content)
-        }
-    }
-
-    fn not_found() -> Self { Response::from_status(StatusCode::NotFound) }
-
-    fn forbidden() -> Self { Response::from_status(StatusCode::Forbidden) }
-
-    fn internal_error() -> Self { Response::from_status(StatusCode::InternalServerError) }
-
-    fn add_header(&mut self, name: &str, value: &str) {
-        self.headers.push(HeaderField {
-            name: name.to_string(),
-            value: value.to_string()
-        })
-    }
-
-    async fn write_header<'b>(&self, stream: &mut WriteHalf<'b>) -> Result<()> {
-        let (code, msg) = match self.code {
-            StatusCode::HttpOk => (200, "OK"),
-            StatusCode::Created => (201, "Created"),
-            StatusCode::NotFound => (404, "Not Found"),
-            StatusCode::Forbidden => (403, "Forbidden"),
-            StatusCode::InternalServerError => (500, "Internal Server Error"),
-        };
-        let status_line = format!("HTTP/1.1 {} {}\r\n", code, msg);
-        stream.write(status_line.as_bytes()).await?;
-        for header in self.headers.iter() {
-            let output = format!("{}: {}\r\n", header.name, header.value);
-            stream.write(output.as_bytes()).await?;
-        }
-
-        // End of header
-        stream.write(b"\r\n").await?;
-        stream.flush().await?;
-        Ok(())
-    }
-}
-
-#[derive(Clone, Debug)]
-struct Configuration {
-    root_dir: Option<PathBuf>,
-}
 
-type HandlerReturn<'a> = Result<Response<'a>>;
-type PinnedReturn<'a> = Pin<Box<dyn Future<Output=HandlerReturn<'a>> + Send + 'a>>;
-type Handler = for<'a> fn(&'a Configuration, Request<'a>) -> PinnedReturn<'a>;
-
-#[derive(Clone, PartialEq)]
-enum HttpVerb {
-    Unknown,
-    Any,
-    Get,
-    Post,
-}
-
-#[derive(Clone)]
-struct Route
-{
-    verb: HttpVerb,
-    path: PathBuf,
-    exact: bool, // If true, the path must match `prefix` exactly
-                 // Otherwise, this is a prefix
-    handler: RouteTarget,
-}
-
-#[derive(Clone)]
-enum RouteTarget {
-    Static(StatusCode),
-    Dynamic(Handler),
-}
-
-impl Into<RouteTarget> for Handler {
-    fn into(self) -> RouteTarget {
-        RouteTarget::Dynamic(self)
-    }
+/// Wraps the connection's body reader so the loop can tell, once the route
+/// handler returns, how many bytes of a declared `Content-Length` body it
+/// actually read. A handler that never looks at the body (the `NotFound`
+/// catch-all a stray `POST` falls through to) or one that bails out before
+/// consuming it all leaves the rest sitting on the wire; without draining
+/// it, those bytes get parsed as the start of the next request.
+struct TrackedReader<'a> {
+    inner: &'a mut Reader<'a>,
+    read: Arc<AtomicUsize>,
 }
 
-impl RouteTarget {
-    async fn invoke<'a>(&'a self, config: &'a Configuration, request: Request<'a>) -> Result<Response> {
-        match self {
-            RouteTarget::Static(code) => {
-                Ok(Response::from_status(code.clone()))
-            },
-            RouteTarget::Dynamic(handler) => {
-                (handler)(config, request).await
-            },
+impl<'a> AsyncRead for TrackedReader<'a> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut *this.inner).poll_read(cx, buf);
+        if poll.is_ready() {
+            this.read.fetch_add(buf.filled().len() - before, Ordering::Relaxed);
         }
+        poll
     }
 }
 
-impl Route {
-    fn new(verb: HttpVerb, path: &str, exact: bool, handler: RouteTarget) -> Self {
-        Self {
-            verb,
-            path: PathBuf::from(path),
-            exact,
-            handler
-        }
-    }
-
-    fn matches(&self, request: &Request) -> Option<usize> {
-        let verb_matches = request.verb == HttpVerb::Any || request.verb == self.verb;
-        let path_matches = if self.exact {
-            self.path == request.path
-        } else {
-            request.path.starts_with(&self.path)
-        };
-
-        if verb_matches && path_matches {
-            Some(self.path.as_os_str().len())
-        } else {
-            None
-        }
-    }
-}
-
-struct Request<'a> {
-    verb: HttpVerb,
-    path: PathBuf,
-    headers: Vec<HeaderField>,
-    body: Option<Payload<'a>>,
-}
-
-impl<'a> Request<'a> {
-    fn new(verb: HttpVerb, path: PathBuf) -> Self {
-        Self {
-            verb,
-            path,
-            headers: vec![],
-            body: None
-        }
-    }
-
-    fn add_header(&mut self, name: &str, value: &str) {
-        self.headers.push(HeaderField {
-            name: name.to_string(),
-            value: value.to_string()
-        })
-    }
-
-    fn get_header(&self, needle: &str) -> Option<String> {
-        for HeaderField { name, value } in &self.headers {
-            if name == needle {
-                return Some(value.to_string())
-            }
-        }
-        None
-    }
-
-    fn set_payload(&mut self, payload: Payload<'a>) {
-        self.body = Some(payload)
+impl<'a> AsyncBufRead for TrackedReader<'a> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        Pin::new(&mut *self.get_mut().inner).poll_fill_buf(cx)
     }
 
-    fn content_length(&self) -> Option<usize> {
-        self.get_header("Content-Length")
-            .map(|value| value.parse::<usize>().unwrap())
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        Pin::new(&mut *this.inner).consume(amt);
+        this.read.fetch_add(amt, Ordering::Relaxed);
     }
-
-    fn strip_path_prefix(req: Request<'a>, pref_length: usize) -> Self {
-        let parts = req.path
-            .as_os_str()
-            .as_bytes()
-            .split_at(pref_length);
-        Self {
-            verb: req.verb,
-            path: PathBuf::from(OsStr::from_bytes(parts.1)),
-            headers: req.headers,
-            body: req.body,
-        }
-    }
-}
-
-fn build_error<T>(kind: ErrorKind, msg: &str) -> Result<T> {
-    Err(io::Error::new(kind, msg).into())
 }
 
-async fn parse_query<'a>(mut reader: Box<Reader<'a>>) -> Result<Request<'a>>
+/// How long a connection may sit with no request in flight before it's
+/// considered abandoned and reaped without a response -- covers half-open
+/// sockets and keep-alive connections the client never reuses.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long a client has, once its request line has been read, to finish
+/// sending the headers. Exceeding it gets a `408 Request Timeout` rather
+/// than a silent drop, since the server has already committed to a request.
+const SLOW_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Reads and parses just the request line (`METHOD /path HTTP/x.y`).
+/// Returns `Ok(None)` on a clean EOF, which callers should treat as "the
+/// client is done with this connection", not an error.
+async fn read_request_line<R>(reader: &mut R) -> Result<Option<(HttpVerb, PathBuf, HttpVersion)>>
+where
+    R: AsyncBufRead + Unpin,
 {
     let mut buf = String::new();
-    reader.read_line(&mut buf).await?;
+    let size = reader.read_line(&mut buf).await?;
+    if size == 0 {
+        return Ok(None);
+    }
+
     let parts = buf.split_whitespace().collect::<Vec<_>>();
     let verb = match parts.get(0) {
         Some(&"GET") => HttpVerb::Get,
@@ -257,11 +97,22 @@ async fn parse_query<'a>(mut reader: Box<Reader<'a>>) -> Result<Request<'a>>
                 Ok(PathBuf::from(path))
             }
         )?;
+    let version = match parts.get(2) {
+        Some(&"HTTP/1.0") => HttpVersion::Http10,
+        _ => HttpVersion::Http11,
+    };
 
-    let mut request = Request::new(verb, path);
+    Ok(Some((verb, path, version)))
+}
 
-    buf.clear();
-    while let Ok(size) = reader.read_line(&mut buf).await {
+async fn read_headers<'a, R>(request: &mut Request<'a>, reader: &mut R) -> Result<()>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut buf = String::new();
+    loop {
+        buf.clear();
+        let size = reader.read_line(&mut buf).await?;
         if size == 0 {
             return build_error(
                 ErrorKind::InvalidData,
@@ -280,141 +131,134 @@ async fn parse_query<'a>(mut reader: Box<Reader<'a>>) -> Result<Request<'a>>
                 )
             };
         }
-        buf.clear();
     }
 
-    request.set_payload(Payload::ReadStream(reader));
-
-    Ok(request)
-}
-
-fn handle_echo<'a>(_config: &Configuration, request: Request<'a>) -> PinnedReturn<'a> {
-    Box::pin(async move {
-        let text = request.path.as_os_str().as_bytes().to_vec();
-        let length = text.len().to_string();
-
-        let mut response = Response::ok(Payload::Simple(vec![text]));
-        response.add_header("Content-Type", "text/plain");
-        response.add_header("Content-Length", &length);
-
-        Ok(response)
-    })
+    Ok(())
 }
 
-fn handle_user_agent<'a>(_config: &Configuration, request: Request<'a>) -> PinnedReturn<'a> {
-    Box::pin(async move {
-        if let Some(agent) = request.get_header("User-Agent") {
-            let length = agent.len().to_string();
-            let mut response = Response::ok(Payload::Simple(vec![agent.into_bytes()]));
-            response.add_header("Content-Type", "text/plain");
-            response.add_header("Content-Length", &length);
-
-            Ok(response)
-        } else {
-            build_error(
-                ErrorKind::InvalidData,
-                "Expected User-Agent header, but not found",
-            )
-        }
-    })
-}
+async fn handle_connection(config: &Configuration, mut stream: TcpStream, routes: &[Route]) -> Result<()> {
+    let (read, mut write) = stream.split();
+    let mut reader = BufReader::new(read);
 
-fn handle_download_file<'a>(config: &'a Configuration, request: Request<'a>) -> PinnedReturn<'a> {
-    Box::pin(async move {
-        let mut full_path = match &config.root_dir {
-            Some(base_dir) => base_dir.clone(),
-            None => env::current_dir()?,
+    loop {
+        let (verb, path, version) = match timeout(IDLE_TIMEOUT, read_request_line(&mut reader)).await {
+            Ok(Ok(Some(parsed))) => parsed,
+            Ok(Ok(None)) => break,
+            Ok(Err(error)) => return Err(error),
+            Err(_) => break,
         };
-        full_path.push(request.path);
 
-        match File::open(full_path).await {
-            Ok(file) => {
-                let size = file.metadata().await?.len();
-                let mut response = Response::ok(
-                    Payload::ReadStream(Box::new(BufReader::new(file)))
-                );
-                response.add_header("Content-Length", &size.to_string());
-                response.add_header("Content-Type", "application/octet-stream");
-                response.add_header("Content-Disposition", "attachment");
-                Ok(response)
-            }
-            Err(error) => match error.kind() {
-                ErrorKind::NotFound => Ok(Response::not_found()),
-                ErrorKind::PermissionDenied => Ok(Response::forbidden()),
-                _ => Ok(Response::internal_error()),
+        let mut request = Request::new(verb, path, version);
+
+        match timeout(SLOW_REQUEST_TIMEOUT, read_headers(&mut request, &mut reader)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(error)) => return Err(error),
+            Err(_) => {
+                Response::from_status(StatusCode::RequestTimeout).write_header(&mut write).await?;
+                break;
             }
         }
-    })
-}
-
-const COPY_BUFFER_DEFAULT_SIZE: usize = 1024;
-
-async fn copy_bytes<'a>(reader: &mut Reader<'a>, writer: &mut Writer, len: usize, buf_size: usize) -> Result<usize> {
-    let mut remaining = len;
-
-    while remaining > 0 {
-        let mut buffer = vec![0; std::cmp::min(buf_size, remaining)];
-        remaining -= reader.read_exact(&mut buffer).await?;
-        writer.write(&buffer).await?;
-    }
-    writer.flush().await?;
 
-    Ok(len - remaining)
-}
+        let keep_alive = request.keep_alive();
+        let encoding = request.negotiate_encoding();
 
-fn handle_upload_file<'a>(config: &'a Configuration, request: Request<'a>) -> PinnedReturn<'a> {
-    Box::pin (async move {
-        let mut full_path = match &config.root_dir {
-            Some(base_dir) => base_dir.clone(),
-            None => env::current_dir()?,
-        };
-        full_path.push(&request.path);
+        // A client sending a (possibly large) body with `Expect:
+        // 100-continue` is waiting on this interim status line before it
+        // starts writing, so it has to go out before the handler -- and
+        // through it, `copy_bytes` -- touches the body stream below.
+        if *request.verb() == HttpVerb::Post && request.expects_continue() {
+            write.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await?;
+            write.flush().await?;
+        }
 
-        match File::create(full_path).await {
-            Ok(mut file) => {
-                if let (Some(length), Some(Payload::ReadStream(mut reader))) = (request.content_length(), request.body) {
-                    // TODO: Should probably check the actual read size
-                    copy_bytes(&mut reader, &mut file, length, COPY_BUFFER_DEFAULT_SIZE).await?;
-                    Ok(Response::from_status(StatusCode::Created))
-                } else {
-                    Ok(Response::internal_error())
+        // Track how much of a declared body the handler actually reads, so
+        // any leftover bytes can be drained before `reader` is reused for
+        // the next request line -- see `TrackedReader`.
+        let body_len = request.content_length();
+        let body_read = Arc::new(AtomicUsize::new(0));
+        let tracked = TrackedReader { inner: &mut reader, read: body_read.clone() };
+        request.set_payload(Payload::ReadStream(Box::new(tracked), None));
+
+        // Pick the route with the most matching literal segments, so a literal
+        // route always wins over a `:param`/catch-all one that matches the same
+        // request.
+        let mut best: Option<(&Route, RouteMatch)> = None;
+        for route in routes {
+            if let Some(route_match) = route.matches(&request) {
+                let is_better = match &best {
+                    None => true,
+                    Some((_, current)) => route_match.literal_count > current.literal_count,
+                };
+                if is_better {
+                    best = Some((route, route_match));
                 }
             }
-            Err(error) => match error.kind() {
-                ErrorKind::NotFound => Ok(Response::not_found()),
-                ErrorKind::PermissionDenied => Ok(Response::forbidden()),
-                _ => Ok(Response::internal_error()),
-            }
         }
-    })
-}
-
-async fn handle_connection(config: &Configuration, mut stream: TcpStream, routes: &[Route]) -> Result<()> {
-    let (read, mut write) = stream.split();
-    let reader = BufReader::new(read);
 
-    let request = parse_query(Box::new(reader)).await?;
+        if let Some((route, route_match)) = best {
+            let request = if route_match.params.is_empty() {
+                Request::strip_path_prefix(request, route_match.prefix_len)
+            } else {
+                request
+            };
+            let mut response = route.handle(config, request, route_match.params).await?;
+
+            // Whatever the handler didn't read of a declared `Content-Length`
+            // body is still sitting on the wire ahead of the next request
+            // line -- drain it now rather than let it desync the connection.
+            if let Some(len) = body_len {
+                let remaining = len.saturating_sub(body_read.load(Ordering::Relaxed));
+                if remaining > 0 {
+                    copy_bytes(&mut reader, &mut io::sink(), remaining, COPY_BUFFER_DEFAULT_SIZE).await?;
+                }
+            }
 
-    for route in routes {
-        if let Some(size) = route.matches(&request) {
-            let response = route.handler.invoke(
-                config,
-                Request::strip_path_prefix(request, size)
-            ).await?;
+            response.add_header("Connection", if keep_alive { "keep-alive" } else { "close" });
+
+            // Compress whole-body stream responses (e.g. file downloads) on
+            // the fly when the client asked for it. Range responses keep a
+            // fixed byte length tied to the uncompressed representation, so
+            // they're left alone; handlers that already compress their own
+            // `Payload::Simple` body (like `/echo/`) are untouched too.
+            if encoding != Encoding::Identity {
+                match response.payload() {
+                    Some(Payload::ReadStream(reader, None)) => {
+                        let wrapped: Box<Reader> = match encoding {
+                            Encoding::Gzip => Box::new(BufReader::new(GzipEncoder::new(reader))),
+                            Encoding::Deflate => Box::new(BufReader::new(DeflateEncoder::new(reader))),
+                            Encoding::Identity => unreachable!(),
+                        };
+                        response.remove_header("Content-Length");
+                        response.add_header("Content-Encoding", encoding.token());
+                        response.set_payload(Payload::ReadStream(wrapped, None));
+                    }
+                    Some(other) => response.set_payload(other),
+                    None => {}
+                }
+            }
 
-            response.write_header(&mut write).await?;
-            if let Some(payload) = response.payload {
+            let chunked = response.write_header(&mut write).await?;
+            if let Some(payload) = response.payload() {
                 match payload {
-                    Payload::Simple(response) => {
-                        for block in response {
-                            write.write(&block).await?;
+                    Payload::Simple(blocks) => {
+                        for block in blocks {
+                            write.write_all(&block).await?;
                         }
                     }
-                    Payload::ReadStream(mut stream) => {
+                    Payload::ReadStream(mut stream, Some(len)) => {
+                        copy_bytes(&mut *stream, &mut write, len, COPY_BUFFER_DEFAULT_SIZE).await?;
+                    }
+                    Payload::ReadStream(mut stream, None) if chunked => {
+                        write_chunked_body(&mut *stream, &mut write).await?;
+                    }
+                    Payload::ReadStream(mut stream, None) => {
                         io::copy_buf(&mut stream, &mut write).await?;
                     }
                 }
             }
+        }
+
+        if !keep_alive {
             break;
         }
     }
@@ -425,35 +269,20 @@ async fn handle_connection(config: &Configuration, mut stream: TcpStream, routes
 fn declare_routes() -> Vec<Route> {
     vec![
         Route::new(HttpVerb::Get, "/", true, RouteTarget::Static(StatusCode::HttpOk)),
-        Route::new(HttpVerb::Get, "/echo/", false, RouteTarget::Dynamic(handle_echo)),
+        Route::new(HttpVerb::Get, "/echo/:word", true, RouteTarget::Dynamic(handle_echo)),
         Route::new(HttpVerb::Get,"/user-agent", true, RouteTarget::Dynamic(handle_user_agent)),
-        Route::new(HttpVerb::Get,"/files/", false, RouteTarget::Dynamic(handle_download_file)),
-        Route::new(HttpVerb::Post, "/files/", false, RouteTarget::Dynamic(handle_upload_file)),
+        Route::new(HttpVerb::Get, "/files/*path", true, RouteTarget::Dynamic(handle_serve_dir)),
+        Route::new(HttpVerb::Post, "/files/*path", true, RouteTarget::Dynamic(handle_upload_file)),
         // The default, it matches anything
         Route::new(HttpVerb::Any,"", false, RouteTarget::Static(StatusCode::NotFound)),
     ]
 }
 
-fn get_configuration() -> Configuration {
-    let mut directory: Option<PathBuf> = None;
-    let args: Vec<String> = env::args().collect();
-
-    if args.get(1) == Some(&"--directory".to_string()) {
-        if let Some(path) = args.get(2) {
-            directory = Some(PathBuf::from(path));
-        }
-    }
-
-    Configuration {
-        root_dir: directory,
-    }
-}
-
 const SERVER_ADDRESS: &str = "127.0.0.1:4221";
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let config = get_configuration();
+    let config = Configuration::get();
     let listener = TcpListener::bind(SERVER_ADDRESS).await?;
     let routes = declare_routes();
 
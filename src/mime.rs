@@ -0,0 +1,41 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Maps a path's extension to the media type the static file server should
+/// advertise for it, preferring an operator-registered override (see
+/// `Configuration::register_mime_type`) over the built-in table, and
+/// falling back to `application/octet-stream` for extensions neither knows.
+pub fn content_type_for(path: &Path, overrides: &HashMap<String, String>) -> String {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+
+    if let Some(content_type) = overrides.get(&extension) {
+        return content_type.clone();
+    }
+
+    match extension.as_str() {
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "js" => "text/javascript",
+        "json" => "application/json",
+        "txt" => "text/plain",
+        "pdf" => "application/pdf",
+        "wasm" => "application/wasm",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "ico" => "image/x-icon",
+        _ => "application/octet-stream",
+    }
+    .to_string()
+}
+
+/// Whether a media type is one browsers render inline, as opposed to one
+/// that should be offered as a download via `Content-Disposition: attachment`.
+pub fn is_inline_renderable(content_type: &str) -> bool {
+    content_type != "application/octet-stream"
+}
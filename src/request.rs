@@ -1,25 +1,60 @@
-use crate::{HeaderField, HttpVerb, Payload};
+use crate::{HeaderField, HttpVerb, HttpVersion, Payload};
+use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Deflate,
+    Identity,
+}
+
+impl Encoding {
+    pub fn token(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+            Encoding::Identity => "identity",
+        }
+    }
+}
 
 pub struct Request<'a> {
     verb: HttpVerb,
     path: PathBuf,
+    version: HttpVersion,
     headers: Vec<HeaderField>,
     body: Option<Payload<'a>>,
+    params: HashMap<String, String>,
 }
 
 impl<'a> Request<'a> {
-    pub fn new(verb: HttpVerb, path: PathBuf) -> Self {
+    pub fn new(verb: HttpVerb, path: PathBuf, version: HttpVersion) -> Self {
         Self {
             verb,
             path,
+            version,
             headers: vec![],
             body: None,
+            params: HashMap::new(),
         }
     }
 
+    pub fn set_params(&mut self, params: HashMap<String, String>) {
+        self.params = params;
+    }
+
+    pub fn set_param(&mut self, name: &str, value: String) {
+        self.params.insert(name.to_string(), value);
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(String::as_str)
+    }
+
     pub fn verb(&'a self) -> &'a HttpVerb {
         &self.verb
     }
@@ -57,13 +92,114 @@ impl<'a> Request<'a> {
             .map(|value| value.parse::<usize>().unwrap())
     }
 
+    pub fn range_header(&self) -> Option<String> {
+        self.get_header("Range")
+    }
+
+    pub fn if_none_match(&self) -> Option<String> {
+        self.get_header("If-None-Match")
+    }
+
+    pub fn if_modified_since(&self) -> Option<SystemTime> {
+        self.get_header("If-Modified-Since")
+            .and_then(|value| crate::http_date::parse_http_date(&value))
+    }
+
+    /// Whether the client is holding off on sending the body until it sees
+    /// an interim `100 Continue`.
+    pub fn expects_continue(&self) -> bool {
+        self.get_header("Expect")
+            .map(|value| value.eq_ignore_ascii_case("100-continue"))
+            .unwrap_or(false)
+    }
+
+    /// Whether the connection should stay open for another request once this
+    /// one has been answered: an explicit `Connection` header always wins,
+    /// otherwise it's the default for the request's HTTP version (HTTP/1.1
+    /// defaults to keep-alive, HTTP/1.0 to close).
+    pub fn keep_alive(&self) -> bool {
+        match self.get_header("Connection").map(|value| value.to_lowercase()) {
+            Some(value) if value == "close" => false,
+            Some(value) if value == "keep-alive" => true,
+            _ => self.version == HttpVersion::Http11,
+        }
+    }
+
+    /// Negotiates a response encoding against the `Accept-Encoding` header,
+    /// honoring q-values, `*` wildcards, and the implicit acceptability of
+    /// `identity`. Returns the highest-ranked codec this server supports
+    /// (preferring gzip, then deflate, then identity) that the client finds
+    /// acceptable.
+    pub fn negotiate_encoding(&self) -> Encoding {
+        const PREFERENCE: [Encoding; 3] = [Encoding::Gzip, Encoding::Deflate, Encoding::Identity];
+
+        let header = match self.get_header("Accept-Encoding") {
+            Some(header) => header,
+            None => return Encoding::Identity,
+        };
+
+        let mut q_values: HashMap<String, f32> = HashMap::new();
+        let mut wildcard_q: Option<f32> = None;
+
+        for entry in header.split(',') {
+            let entry = entry.trim();
+            if entry.is_empty() {
+                continue;
+            }
+            let mut fields = entry.split(';');
+            let token = fields.next().unwrap_or("").trim();
+            let q = fields
+                .find_map(|field| field.trim().strip_prefix("q="))
+                .and_then(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            if token == "*" {
+                wildcard_q = Some(q);
+            } else {
+                q_values.insert(token.to_lowercase(), q);
+            }
+        }
+
+        let acceptable = |codec: &str| -> f32 {
+            if let Some(&q) = q_values.get(codec) {
+                q
+            } else if let Some(q) = wildcard_q {
+                q
+            } else if codec == "identity" {
+                1.0
+            } else {
+                0.0
+            }
+        };
+
+        let mut best: Option<(Encoding, f32)> = None;
+        for encoding in PREFERENCE {
+            let q = acceptable(encoding.token());
+            if q <= 0.0 {
+                continue;
+            }
+            match best {
+                Some((_, best_q)) if q <= best_q => {}
+                _ => best = Some((encoding, q)),
+            }
+        }
+
+        best.map(|(encoding, _)| encoding).unwrap_or(Encoding::Identity)
+    }
+
     pub fn strip_path_prefix(req: Request<'a>, pref_length: usize) -> Self {
         let parts = req.path.as_os_str().as_bytes().split_at(pref_length);
         Self {
             verb: req.verb,
             path: PathBuf::from(OsStr::from_bytes(parts.1)),
+            version: req.version,
             headers: req.headers,
             body: req.body,
+            params: req.params,
         }
     }
+
+    pub fn with_path(req: Request<'a>, path: PathBuf) -> Self {
+        Self { path, ..req }
+    }
 }
@@ -41,6 +41,11 @@ impl<'a> Response<'a> {
     pub fn payload(&mut self) -> Option<Payload> {
         self.payload.take()
     }
+
+    pub fn set_payload(&mut self, payload: Payload<'a>) {
+        self.payload = Some(payload)
+    }
+
     pub fn add_header(&mut self, name: &str, value: &str) {
         self.headers.push(HeaderField {
             name: name.to_string(),
@@ -48,12 +53,35 @@ impl<'a> Response<'a> {
         })
     }
 
-    pub async fn write_header<'b>(&self, stream: &mut WriteHalf<'b>) -> Result<()> {
+    /// Drops any header matching `name` (case-insensitively) -- used when
+    /// compressing a response on the fly invalidates a `Content-Length`
+    /// that was set before the final body size was known.
+    pub fn remove_header(&mut self, name: &str) {
+        self.headers.retain(|header| !header.name.eq_ignore_ascii_case(name));
+    }
+
+    /// Whether the body should be framed as `Transfer-Encoding: chunked`
+    /// rather than relying on a `Content-Length`: true for a stream payload
+    /// whose length wasn't known ahead of time and so never got a
+    /// `Content-Length` header attached.
+    fn is_chunked(&self) -> bool {
+        matches!(self.payload, Some(Payload::ReadStream(_, _)))
+            && !self.headers.iter().any(|header| header.name.eq_ignore_ascii_case("Content-Length"))
+    }
+
+    /// Writes the status line and headers, returning whether the body must
+    /// be framed as chunked (see `is_chunked`) so the caller can pick the
+    /// matching body-writing strategy.
+    pub async fn write_header<'b>(&self, stream: &mut WriteHalf<'b>) -> Result<bool> {
         let (code, msg) = match self.code {
             StatusCode::HttpOk => (200, "OK"),
             StatusCode::Created => (201, "Created"),
+            StatusCode::PartialContent => (206, "Partial Content"),
+            StatusCode::NotModified => (304, "Not Modified"),
             StatusCode::NotFound => (404, "Not Found"),
             StatusCode::Forbidden => (403, "Forbidden"),
+            StatusCode::RequestTimeout => (408, "Request Timeout"),
+            StatusCode::RangeNotSatisfiable => (416, "Range Not Satisfiable"),
             StatusCode::InternalServerError => (500, "Internal Server Error"),
         };
         let status_line = format!("HTTP/1.1 {} {}\r\n", code, msg);
@@ -63,9 +91,24 @@ impl<'a> Response<'a> {
             stream.write_all(output.as_bytes()).await?;
         }
 
+        let chunked = self.is_chunked();
+        if chunked {
+            stream.write_all(b"Transfer-Encoding: chunked\r\n").await?;
+        } else if self.payload.is_none()
+            && !matches!(self.code, StatusCode::NotModified)
+            && !self.headers.iter().any(|header| header.name.eq_ignore_ascii_case("Content-Length"))
+        {
+            // A bodiless response (404, 403, 201, a bare 200, ...) still
+            // needs a delimiter on a keep-alive connection, or the client
+            // has no way to tell where it ends and the next response
+            // begins. 304 is exempt: per RFC 7230 3.3.3 it never has a body
+            // regardless of headers, so clients don't need one here either.
+            stream.write_all(b"Content-Length: 0\r\n").await?;
+        }
+
         // End of header
         stream.write_all(b"\r\n").await?;
         stream.flush().await?;
-        Ok(())
+        Ok(chunked)
     }
 }
@@ -3,7 +3,9 @@ use crate::request::Request;
 use crate::response::Response;
 use crate::{Handler, HttpVerb, StatusCode};
 use anyhow::Result;
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::{Component, Path, PathBuf};
 
 #[derive(Clone)]
 pub struct Route {
@@ -15,6 +17,17 @@ pub struct Route {
     handler: RouteTarget,
 }
 
+/// The outcome of a successful `Route::matches` call: how many literal
+/// (non-parameter) segments were matched -- used to rank candidate routes --
+/// the named path parameters captured along the way, and (for routes with
+/// no parameters) the byte length of the matched prefix, kept for handlers
+/// that still expect the request path stripped down to its tail.
+pub struct RouteMatch {
+    pub literal_count: usize,
+    pub prefix_len: usize,
+    pub params: HashMap<String, String>,
+}
+
 #[derive(Clone)]
 pub enum RouteTarget {
     Static(StatusCode),
@@ -50,26 +63,96 @@ impl Route {
         }
     }
 
-    pub fn matches(&self, request: &Request) -> Option<usize> {
+    fn normal_segments(path: &Path) -> Vec<&OsStr> {
+        path.components()
+            .filter_map(|component| match component {
+                Component::Normal(segment) => Some(segment),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Matches `request` against this route, comparing path components
+    /// segment-by-segment so that a `:name` segment captures whatever the
+    /// request has in that position, and a trailing `*name` segment (a
+    /// catch-all) captures every remaining request segment -- zero or more
+    /// of them -- joined back together with `/`.
+    pub fn matches(&self, request: &Request) -> Option<RouteMatch> {
         let verb_matches = self.verb == HttpVerb::Any || request.verb() == &self.verb;
-        let path_matches = if self.exact {
-            self.path == request.path()
+        if !verb_matches {
+            return None;
+        }
+
+        let route_segments = Self::normal_segments(&self.path);
+        let request_segments = Self::normal_segments(request.path());
+
+        let catch_all = route_segments
+            .last()
+            .and_then(|segment| segment.to_str())
+            .and_then(|segment| segment.strip_prefix('*'));
+
+        let fixed_len = if catch_all.is_some() {
+            route_segments.len() - 1
         } else {
-            request.path().starts_with(&self.path)
+            route_segments.len()
         };
 
-        if verb_matches && path_matches {
-            Some(self.path.as_os_str().len())
-        } else {
-            None
+        if catch_all.is_none() {
+            if self.exact && route_segments.len() != request_segments.len() {
+                return None;
+            }
+            if request_segments.len() < route_segments.len() {
+                return None;
+            }
+        } else if request_segments.len() < fixed_len {
+            return None;
         }
+
+        let mut literal_count = 0;
+        let mut params = HashMap::new();
+
+        for (route_segment, request_segment) in
+            route_segments.iter().take(fixed_len).zip(request_segments.iter())
+        {
+            let route_segment = route_segment.to_str()?;
+            let request_segment = request_segment.to_str()?;
+            if let Some(name) = route_segment.strip_prefix(':') {
+                params.insert(name.to_string(), request_segment.to_string());
+            } else if route_segment == request_segment {
+                literal_count += 1;
+            } else {
+                return None;
+            }
+        }
+
+        if let Some(name) = catch_all {
+            let tail = request_segments[fixed_len..]
+                .iter()
+                .filter_map(|segment| segment.to_str())
+                .collect::<Vec<_>>()
+                .join("/");
+            params.insert(name.to_string(), tail);
+        }
+
+        // Routes with no captured parameters keep exposing the byte length
+        // of their literal prefix, for handlers still relying on
+        // `Request::strip_path_prefix`.
+        let prefix_len = if params.is_empty() {
+            self.path.as_os_str().len()
+        } else {
+            0
+        };
+
+        Some(RouteMatch { literal_count, prefix_len, params })
     }
 
     pub async fn handle<'a>(
         &'a self,
         config: &'a Configuration,
-        request: Request<'a>,
+        mut request: Request<'a>,
+        params: HashMap<String, String>,
     ) -> Result<Response> {
+        request.set_params(params);
         self.handler.invoke(config, request).await
     }
 }